@@ -0,0 +1,335 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use http_body::{Body, Frame, SizeHint};
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::Counter;
+use pin_project::pin_project;
+use tonic::Code;
+
+use crate::Metrics;
+
+/// Bookkeeping that only applies to the response-side body: it is the one
+/// whose end-of-stream marks the RPC as handled.
+struct ResponseState {
+    metrics: Metrics,
+    started_at: Instant,
+    header_code: Option<Code>,
+    finished: bool,
+}
+
+/// Wraps a request or response body to observe streamed messages as they
+/// pass through, without buffering them.
+///
+/// Every data frame increments `message_counter`. When this wraps a
+/// response body, the terminal trailers frame (or, failing that, the
+/// initial response headers) is where the final `grpc-status` is read and
+/// `handled_total`/`handling_duration`/`active_requests` are recorded -
+/// this is what lets long-lived server streams be measured correctly
+/// instead of being counted as done the moment headers arrive.
+#[pin_project]
+pub struct MetricsBody<B> {
+    #[pin]
+    inner: B,
+    labels: Arc<[KeyValue]>,
+    message_counter: Counter<u64>,
+    message_count: u64,
+    response_state: Option<ResponseState>,
+}
+
+impl<B> MetricsBody<B> {
+    pub(crate) fn for_request(
+        inner: B,
+        labels: Arc<[KeyValue]>,
+        message_counter: Counter<u64>,
+    ) -> Self {
+        MetricsBody {
+            inner,
+            labels,
+            message_counter,
+            message_count: 0,
+            response_state: None,
+        }
+    }
+
+    pub(crate) fn for_response(
+        inner: B,
+        labels: Arc<[KeyValue]>,
+        message_counter: Counter<u64>,
+        metrics: Metrics,
+        started_at: Instant,
+        header_code: Option<Code>,
+    ) -> Self {
+        MetricsBody {
+            inner,
+            labels,
+            message_counter,
+            message_count: 0,
+            response_state: Some(ResponseState {
+                metrics,
+                started_at,
+                header_code,
+                finished: false,
+            }),
+        }
+    }
+}
+
+fn finish(state: &mut ResponseState, labels: &[KeyValue], message_count: u64, code: Code) {
+    if state.finished {
+        return;
+    }
+    state.finished = true;
+
+    let grpc_type = if message_count > 1 {
+        "server_stream"
+    } else {
+        "unary"
+    };
+    let mut smc_labels = labels.to_vec();
+    smc_labels.push(KeyValue::new("grpc_code", format!("{:?}", code)));
+    smc_labels.push(KeyValue::new("grpc_type", grpc_type));
+
+    let elapsed = state.started_at.elapsed().as_secs_f64();
+    state.metrics.active_requests.add(-1, labels);
+    state.metrics.handled_total.add(1, &smc_labels);
+    state.metrics.handling_duration.record(elapsed, &smc_labels);
+}
+
+fn trailer_code(frame: &Frame<impl bytes::Buf>) -> Option<Code> {
+    frame
+        .trailers_ref()
+        .and_then(|trailers| trailers.get("grpc-status"))
+        .map(|status| Code::from_bytes(status.as_bytes()))
+}
+
+impl<B> Body for MetricsBody<B>
+where
+    B: Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_frame(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) if frame.is_data() => {
+                *this.message_count += 1;
+                this.message_counter.add(1, this.labels);
+            }
+            Poll::Ready(Some(Ok(frame))) if frame.is_trailers() => {
+                if let Some(state) = this.response_state {
+                    let code = trailer_code(frame).or(state.header_code).unwrap_or(Code::Ok);
+                    finish(state, this.labels, *this.message_count, code);
+                }
+            }
+            Poll::Ready(None) => {
+                if let Some(state) = this.response_state {
+                    let code = state.header_code.unwrap_or(Code::Ok);
+                    finish(state, this.labels, *this.message_count, code);
+                }
+            }
+            _ => {}
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let finished = self.response_state.as_ref().is_none_or(|s| s.finished);
+        finished && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData};
+    use opentelemetry_sdk::metrics::{ManualReader, SdkMeterProvider};
+
+    use super::*;
+
+    /// A body that replays a fixed, scripted sequence of frames, so the
+    /// `poll_frame`/`is_end_stream` state machine can be driven through
+    /// trailers-only, data+trailers, and data-with-no-trailers/hang-up
+    /// cases without a live gRPC server.
+    struct ScriptedBody {
+        frames: VecDeque<Frame<Bytes>>,
+    }
+
+    impl Body for ScriptedBody {
+        type Data = Bytes;
+        type Error = std::convert::Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Ready(self.frames.pop_front().map(Ok))
+        }
+    }
+
+    fn trailers_frame(code: Code) -> Frame<Bytes> {
+        let mut trailers = tonic::codegen::http::HeaderMap::new();
+        trailers.insert("grpc-status", format!("{}", code as i32).parse().unwrap());
+        Frame::trailers(trailers)
+    }
+
+    fn test_metrics() -> (Metrics, ManualReader) {
+        let reader = ManualReader::builder().build();
+        let provider = SdkMeterProvider::builder().with_reader(reader.clone()).build();
+        let meter = provider.meter("tonic-test");
+        let metrics = Metrics {
+            started_total: meter.u64_counter("grpc_server_started").build(),
+            handled_total: meter.u64_counter("grpc_server_handled").build(),
+            handling_duration: meter
+                .f64_histogram("grpc_server_handling_duration_seconds")
+                .build(),
+            active_requests: meter.i64_up_down_counter("grpc_server_active_requests").build(),
+            msg_received: meter.u64_counter("grpc_server_msg_received").build(),
+            msg_sent: meter.u64_counter("grpc_server_msg_sent").build(),
+        };
+        (metrics, reader)
+    }
+
+    fn sum_of(reader: &ManualReader, instrument: &str) -> i64 {
+        let mut data = opentelemetry_sdk::metrics::data::ResourceMetrics {
+            resource: opentelemetry_sdk::Resource::builder().build(),
+            scope_metrics: Vec::new(),
+        };
+        reader.collect(&mut data).expect("collect");
+        data.scope_metrics
+            .iter()
+            .flat_map(|sm| sm.metrics.iter())
+            .filter(|m| m.name == instrument)
+            .map(|m| match &m.data {
+                AggregatedMetrics::U64(MetricData::Sum(sum)) => {
+                    sum.data_points.iter().map(|dp| dp.value as i64).sum::<i64>()
+                }
+                AggregatedMetrics::I64(MetricData::Sum(sum)) => {
+                    sum.data_points.iter().map(|dp| dp.value).sum::<i64>()
+                }
+                _ => 0,
+            })
+            .sum()
+    }
+
+    fn grpc_type_of(reader: &ManualReader, instrument: &str) -> Option<String> {
+        let mut data = opentelemetry_sdk::metrics::data::ResourceMetrics {
+            resource: opentelemetry_sdk::Resource::builder().build(),
+            scope_metrics: Vec::new(),
+        };
+        reader.collect(&mut data).expect("collect");
+        data.scope_metrics.iter().find_map(|sm| {
+            sm.metrics.iter().find(|m| m.name == instrument).and_then(|m| match &m.data {
+                AggregatedMetrics::U64(MetricData::Sum(sum)) => sum.data_points.iter().find_map(|dp| {
+                    dp.attributes
+                        .iter()
+                        .find(|kv| kv.key.as_str() == "grpc_type")
+                        .map(|kv| kv.value.to_string())
+                }),
+                _ => None,
+            })
+        })
+    }
+
+    async fn drain(body: MetricsBody<ScriptedBody>) {
+        let mut body = body;
+        while body.frame().await.is_some() {}
+    }
+
+    #[tokio::test]
+    async fn trailers_only_response_finishes_exactly_once() {
+        let (metrics, reader) = test_metrics();
+        let labels: Arc<[KeyValue]> = Arc::from(vec![KeyValue::new("grpc_service", "svc")]);
+        let scripted = ScriptedBody {
+            frames: VecDeque::from([trailers_frame(Code::Ok)]),
+        };
+        // `MetricsFuture` records the start; the body only records the end.
+        metrics.active_requests.add(1, &labels);
+        let body = MetricsBody::for_response(
+            scripted,
+            labels,
+            metrics.msg_sent.clone(),
+            metrics.clone(),
+            Instant::now(),
+            None,
+        );
+
+        drain(body).await;
+
+        assert_eq!(sum_of(&reader, "grpc_server_active_requests"), 0);
+        assert_eq!(sum_of(&reader, "grpc_server_handled"), 1);
+        assert_eq!(
+            grpc_type_of(&reader, "grpc_server_handled").as_deref(),
+            Some("unary")
+        );
+    }
+
+    #[tokio::test]
+    async fn data_then_trailers_is_reported_as_server_stream() {
+        let (metrics, reader) = test_metrics();
+        let labels: Arc<[KeyValue]> = Arc::from(vec![KeyValue::new("grpc_service", "svc")]);
+        let scripted = ScriptedBody {
+            frames: VecDeque::from([
+                Frame::data(Bytes::from_static(b"one")),
+                Frame::data(Bytes::from_static(b"two")),
+                trailers_frame(Code::Ok),
+            ]),
+        };
+        let body = MetricsBody::for_response(
+            scripted,
+            labels,
+            metrics.msg_sent.clone(),
+            metrics.clone(),
+            Instant::now(),
+            None,
+        );
+
+        drain(body).await;
+
+        assert_eq!(sum_of(&reader, "grpc_server_handled"), 1);
+        assert_eq!(
+            grpc_type_of(&reader, "grpc_server_handled").as_deref(),
+            Some("server_stream")
+        );
+    }
+
+    #[tokio::test]
+    async fn data_then_hang_up_without_trailers_still_finishes_once() {
+        let (metrics, reader) = test_metrics();
+        let labels: Arc<[KeyValue]> = Arc::from(vec![KeyValue::new("grpc_service", "svc")]);
+        let scripted = ScriptedBody {
+            frames: VecDeque::from([Frame::data(Bytes::from_static(b"one"))]),
+        };
+        // `MetricsFuture` records the start; the body only records the end.
+        metrics.active_requests.add(1, &labels);
+        let body = MetricsBody::for_response(
+            scripted,
+            labels,
+            metrics.msg_sent.clone(),
+            metrics.clone(),
+            Instant::now(),
+            Some(Code::Unavailable),
+        );
+
+        drain(body).await;
+
+        assert_eq!(sum_of(&reader, "grpc_server_active_requests"), 0);
+        assert_eq!(sum_of(&reader, "grpc_server_handled"), 1);
+    }
+}
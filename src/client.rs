@@ -0,0 +1,294 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use http_body::{Body, Frame, SizeHint};
+use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
+use opentelemetry::{KeyValue, global};
+use pin_project::pin_project;
+use tonic::Code;
+use tonic::codegen::http::{request, response};
+use tower::{Layer, Service};
+
+use crate::DEFAULT_HISTOGRAM_BUCKETS;
+
+#[derive(Clone)]
+pub struct ClientMetricsLayer {
+    metrics: ClientMetrics,
+}
+
+#[derive(Clone)]
+pub struct ClientMetrics {
+    pub started_total: Counter<u64>,
+    pub handled_total: Counter<u64>,
+    pub handling_duration: Histogram<f64>,
+    pub active_requests: UpDownCounter<i64>,
+}
+
+#[derive(Default)]
+pub struct ClientMetricsLayerBuilder {
+    buckets: Option<Vec<f64>>,
+    provider: Option<Arc<dyn opentelemetry::metrics::MeterProvider + Send + Sync>>,
+}
+
+impl ClientMetricsLayerBuilder {
+    pub fn new() -> Self {
+        ClientMetricsLayerBuilder::default()
+    }
+    pub fn with_buckets(mut self, buckets: Vec<f64>) -> Self {
+        self.buckets = Some(buckets);
+        self
+    }
+
+    pub fn with_provider<P>(mut self, provider: P) -> Self
+    where
+        P: opentelemetry::metrics::MeterProvider + Send + Sync + 'static,
+    {
+        self.provider = Some(Arc::new(provider));
+        self
+    }
+    pub fn build(self) -> ClientMetricsLayer {
+        let provider = self.provider.unwrap_or_else(|| global::meter_provider());
+
+        let meter = provider.meter("tonic");
+
+        let buckets = self
+            .buckets
+            .unwrap_or_else(|| DEFAULT_HISTOGRAM_BUCKETS.to_vec());
+
+        let started_total = meter
+            .u64_counter("grpc_client_started")
+            .with_description("Total number of RPCs started on the client.")
+            .build();
+        let handled_total = meter
+            .u64_counter("grpc_client_handled")
+            .with_description("Total number of RPCs completed on the client.")
+            .build();
+        let handling_duration = meter
+            .f64_histogram("grpc_client_handling_duration_seconds")
+            .with_description("Rpc call duration")
+            .with_boundaries(buckets)
+            .build();
+        let active_requests = meter
+            .i64_up_down_counter("grpc_client_active_requests")
+            .with_description("Current number of active client requests.")
+            .build();
+        let metrics = ClientMetrics {
+            started_total,
+            handled_total,
+            handling_duration,
+            active_requests,
+        };
+        ClientMetricsLayer { metrics }
+    }
+}
+
+impl<S> Layer<S> for ClientMetricsLayer {
+    type Service = ClientMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClientMetricsService {
+            service: inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClientMetricsService<S> {
+    metrics: ClientMetrics,
+    service: S,
+}
+
+impl<S, B, C> Service<request::Request<B>> for ClientMetricsService<S>
+where
+    S: Service<request::Request<B>, Response = response::Response<C>>,
+{
+    type Response = response::Response<ClientMetricsBody<C>>;
+    type Error = S::Error;
+    type Future = ClientMetricsFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: request::Request<B>) -> Self::Future {
+        let path = req.uri().path();
+        let (service, method) = path.rsplit_once("/").expect("Path must contain a method");
+        let service = service.to_owned();
+        let method = method.to_owned();
+        let metrics = self.metrics.clone();
+        let inner = self.service.call(req);
+        ClientMetricsFuture {
+            inner,
+            metrics,
+            service,
+            method,
+            started_at: None,
+        }
+    }
+}
+
+#[pin_project]
+pub struct ClientMetricsFuture<F> {
+    #[pin]
+    inner: F,
+    metrics: ClientMetrics,
+    service: String,
+    method: String,
+    started_at: Option<Instant>,
+}
+
+impl<F, B, E> Future for ClientMetricsFuture<F>
+where
+    F: Future<Output = Result<response::Response<B>, E>>,
+{
+    type Output = Result<response::Response<ClientMetricsBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let sm_labels = vec![
+            KeyValue::new("grpc_service", this.service.clone()),
+            KeyValue::new("grpc_method", this.method.clone()),
+        ];
+
+        let started_at = *this.started_at.get_or_insert_with(|| {
+            this.metrics.active_requests.add(1, &sm_labels);
+            this.metrics.started_total.add(1, &sm_labels);
+            Instant::now()
+        });
+
+        match this.inner.poll(cx) {
+            Poll::Ready(Ok(response)) => {
+                let header_code = response
+                    .headers()
+                    .get("grpc-status")
+                    .map(|s| Code::from_bytes(s.as_bytes()));
+                let metrics = this.metrics.clone();
+                let response = response.map(|body| {
+                    ClientMetricsBody::new(body, metrics, sm_labels, started_at, header_code)
+                });
+                Poll::Ready(Ok(response))
+            }
+            Poll::Ready(Err(err)) => {
+                let smc_labels = [
+                    KeyValue::new("grpc_service", this.service.clone()),
+                    KeyValue::new("grpc_method", this.method.clone()),
+                    KeyValue::new("grpc_code", format!("{:?}", Code::Unknown)),
+                ];
+                let elapsed = started_at.elapsed().as_secs_f64();
+                this.metrics.active_requests.add(-1, &sm_labels);
+                this.metrics.handled_total.add(1, &smc_labels);
+                this.metrics.handling_duration.record(elapsed, &smc_labels);
+
+                Poll::Ready(Err(err))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Bookkeeping carried alongside the wrapped response body: it is the
+/// terminal trailers frame, not the initial response future, that marks
+/// a client RPC as handled - this is what makes streaming calls measured
+/// correctly instead of mislabeled `Ok` the moment headers arrive.
+struct ResponseState {
+    metrics: ClientMetrics,
+    labels: Vec<KeyValue>,
+    started_at: Instant,
+    header_code: Option<Code>,
+    finished: bool,
+}
+
+#[pin_project]
+pub struct ClientMetricsBody<B> {
+    #[pin]
+    inner: B,
+    state: ResponseState,
+}
+
+impl<B> ClientMetricsBody<B> {
+    pub(crate) fn new(
+        inner: B,
+        metrics: ClientMetrics,
+        labels: Vec<KeyValue>,
+        started_at: Instant,
+        header_code: Option<Code>,
+    ) -> Self {
+        ClientMetricsBody {
+            inner,
+            state: ResponseState {
+                metrics,
+                labels,
+                started_at,
+                header_code,
+                finished: false,
+            },
+        }
+    }
+}
+
+fn finish(state: &mut ResponseState, code: Code) {
+    if state.finished {
+        return;
+    }
+    state.finished = true;
+
+    let mut smc_labels = state.labels.clone();
+    smc_labels.push(KeyValue::new("grpc_code", format!("{:?}", code)));
+
+    let elapsed = state.started_at.elapsed().as_secs_f64();
+    state.metrics.active_requests.add(-1, &state.labels);
+    state.metrics.handled_total.add(1, &smc_labels);
+    state.metrics.handling_duration.record(elapsed, &smc_labels);
+}
+
+fn trailer_code(frame: &Frame<impl bytes::Buf>) -> Option<Code> {
+    frame
+        .trailers_ref()
+        .and_then(|trailers| trailers.get("grpc-status"))
+        .map(|status| Code::from_bytes(status.as_bytes()))
+}
+
+impl<B> Body for ClientMetricsBody<B>
+where
+    B: Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_frame(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) if frame.is_trailers() => {
+                let code = trailer_code(frame)
+                    .or(this.state.header_code)
+                    .unwrap_or(Code::Ok);
+                finish(this.state, code);
+            }
+            Poll::Ready(None) => {
+                let code = this.state.header_code.unwrap_or(Code::Ok);
+                finish(this.state, code);
+            }
+            _ => {}
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.state.finished && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
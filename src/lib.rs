@@ -11,10 +11,21 @@ use tonic::Code;
 use tonic::codegen::http::{request, response};
 use tower::{Layer, Service};
 
-#[derive(Clone)]
-pub struct MetricsLayer {
-    metrics: Metrics,
-}
+mod body;
+mod client;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod tracing_layer;
+pub use body::MetricsBody;
+pub use client::{
+    ClientMetrics, ClientMetricsFuture, ClientMetricsLayer, ClientMetricsLayerBuilder,
+    ClientMetricsService,
+};
+#[cfg(feature = "prometheus")]
+pub use prometheus::{PrometheusScrapeService, prometheus_scrape_service};
+pub use tracing_layer::{
+    TracingBody, TracingFuture, TracingLayer, TracingLayerBuilder, TracingService,
+};
 
 #[derive(Clone)]
 pub struct Metrics {
@@ -22,16 +33,25 @@ pub struct Metrics {
     pub handled_total: Counter<u64>,
     pub handling_duration: Histogram<f64>,
     pub active_requests: UpDownCounter<i64>,
+    pub msg_received: Counter<u64>,
+    pub msg_sent: Counter<u64>,
 }
 
-const DEFAULT_HISTOGRAM_BUCKETS: [f64; 10] = [
+pub(crate) const DEFAULT_HISTOGRAM_BUCKETS: [f64; 10] = [
     0.001, 0.005, 0.01, 0.015, 0.020, 0.025, 0.50, 0.75, 1.0, 2.0,
 ];
 
+/// Extracts extra attributes from a request's metadata to attach to every
+/// metric recorded for that request, e.g. a tenant id from a header. Runs
+/// once per request in [`MetricsService::call`]; return a fixed-size set
+/// to keep metric cardinality bounded.
+pub type AttributeExtractor = Arc<dyn Fn(&request::Parts) -> Vec<KeyValue> + Send + Sync>;
+
 #[derive(Default)]
 pub struct MetricsLayerBuilder {
     buckets: Option<Vec<f64>>,
     provider: Option<Arc<dyn opentelemetry::metrics::MeterProvider + Send + Sync>>,
+    attributes: Option<AttributeExtractor>,
 }
 
 impl MetricsLayerBuilder {
@@ -50,6 +70,14 @@ impl MetricsLayerBuilder {
         self.provider = Some(Arc::new(provider));
         self
     }
+
+    pub fn with_attributes<F>(mut self, attributes: F) -> Self
+    where
+        F: Fn(&request::Parts) -> Vec<KeyValue> + Send + Sync + 'static,
+    {
+        self.attributes = Some(Arc::new(attributes));
+        self
+    }
     pub fn build(self) -> MetricsLayer {
         let provider = self.provider.unwrap_or_else(|| global::meter_provider());
 
@@ -76,16 +104,35 @@ impl MetricsLayerBuilder {
             .i64_up_down_counter("grpc_server_active_requests")
             .with_description("Current number of active server requests.")
             .build();
+        let msg_received = meter
+            .u64_counter("grpc_server_msg_received")
+            .with_description("Total number of stream messages received from the client.")
+            .build();
+        let msg_sent = meter
+            .u64_counter("grpc_server_msg_sent")
+            .with_description("Total number of stream messages sent to the client.")
+            .build();
         let metrics = Metrics {
             started_total,
             handled_total,
             handling_duration,
             active_requests,
+            msg_received,
+            msg_sent,
         };
-        MetricsLayer { metrics }
+        MetricsLayer {
+            metrics,
+            attributes: self.attributes,
+        }
     }
 }
 
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Metrics,
+    attributes: Option<AttributeExtractor>,
+}
+
 impl<S> Layer<S> for MetricsLayer {
     type Service = MetricsService<S>;
 
@@ -93,6 +140,7 @@ impl<S> Layer<S> for MetricsLayer {
         MetricsService {
             service: inner,
             metrics: self.metrics.clone(),
+            attributes: self.attributes.clone(),
         }
     }
 }
@@ -100,14 +148,16 @@ impl<S> Layer<S> for MetricsLayer {
 #[derive(Clone)]
 pub struct MetricsService<S> {
     metrics: Metrics,
+    attributes: Option<AttributeExtractor>,
     service: S,
 }
 
 impl<S, B, C> Service<request::Request<B>> for MetricsService<S>
 where
-    S: Service<request::Request<B>, Response = response::Response<C>>,
+    S: Service<request::Request<MetricsBody<B>>, Response = response::Response<C>>,
+    B: http_body::Body,
 {
-    type Response = S::Response;
+    type Response = response::Response<MetricsBody<C>>;
     type Error = S::Error;
     type Future = MetricsFuture<S::Future>;
 
@@ -116,17 +166,28 @@ where
     }
 
     fn call(&mut self, req: request::Request<B>) -> Self::Future {
-        let path = req.uri().path();
+        let (parts, body) = req.into_parts();
+        let path = parts.uri.path();
         let (service, method) = path.rsplit_once("/").expect("Path must contain a method");
-        let service = service.to_owned();
-        let method = method.to_owned();
         let metrics = self.metrics.clone();
+
+        let mut labels = vec![
+            KeyValue::new("grpc_service", service.to_owned()),
+            KeyValue::new("grpc_method", method.to_owned()),
+        ];
+        if let Some(attributes) = &self.attributes {
+            labels.extend(attributes(&parts));
+        }
+        let sm_labels: Arc<[KeyValue]> = Arc::from(labels);
+
+        let body =
+            MetricsBody::for_request(body, sm_labels.clone(), metrics.msg_received.clone());
+        let req = request::Request::from_parts(parts, body);
         let inner = self.service.call(req);
         MetricsFuture {
             inner,
             metrics,
-            service,
-            method,
+            sm_labels,
             started_at: None,
         }
     }
@@ -137,8 +198,7 @@ pub struct MetricsFuture<F> {
     #[pin]
     inner: F,
     metrics: Metrics,
-    service: String,
-    method: String,
+    sm_labels: Arc<[KeyValue]>,
     started_at: Option<Instant>,
 }
 
@@ -146,42 +206,51 @@ impl<F, B, E> Future for MetricsFuture<F>
 where
     F: Future<Output = Result<response::Response<B>, E>>,
 {
-    type Output = F::Output;
+    type Output = Result<response::Response<MetricsBody<B>>, E>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
 
-        let sm_labels = vec![
-            KeyValue::new("grpc_service", this.service.clone()),
-            KeyValue::new("grpc_method", this.method.clone()),
-        ];
-
-        let started_at = this.started_at.get_or_insert_with(|| {
-            this.metrics.active_requests.add(1, &sm_labels);
-            this.metrics.started_total.add(1, &sm_labels);
+        let started_at = *this.started_at.get_or_insert_with(|| {
+            this.metrics.active_requests.add(1, this.sm_labels);
+            this.metrics.started_total.add(1, this.sm_labels);
             Instant::now()
         });
 
-        if let Poll::Ready(res) = this.inner.poll(cx) {
-            let code = res.as_ref().map_or(Code::Unknown, |resp| {
-                resp.headers()
+        match this.inner.poll(cx) {
+            Poll::Ready(Ok(response)) => {
+                let header_code = response
+                    .headers()
                     .get("grpc-status")
-                    .map(|s| Code::from_bytes(s.as_bytes()))
-                    .unwrap_or(Code::Ok)
-            });
-            let smc_labels = [
-                KeyValue::new("grpc_service", this.service.clone()),
-                KeyValue::new("grpc_method", this.method.clone()),
-                KeyValue::new("grpc_code", format!("{:?}", code)),
-            ];
-            let elapsed = started_at.elapsed().as_secs_f64();
-            this.metrics.active_requests.add(-1, &sm_labels);
-            this.metrics.handled_total.add(1, &smc_labels);
-            this.metrics.handling_duration.record(elapsed, &smc_labels);
-
-            Poll::Ready(res)
-        } else {
-            Poll::Pending
+                    .map(|s| Code::from_bytes(s.as_bytes()));
+                let metrics = this.metrics.clone();
+                let msg_sent = this.metrics.msg_sent.clone();
+                let sm_labels = this.sm_labels.clone();
+                let response = response.map(|body| {
+                    MetricsBody::for_response(
+                        body,
+                        sm_labels,
+                        msg_sent,
+                        metrics,
+                        started_at,
+                        header_code,
+                    )
+                });
+                Poll::Ready(Ok(response))
+            }
+            Poll::Ready(Err(err)) => {
+                let mut smc_labels = this.sm_labels.to_vec();
+                smc_labels.push(KeyValue::new("grpc_code", format!("{:?}", Code::Unknown)));
+                smc_labels.push(KeyValue::new("grpc_type", "unary"));
+
+                let elapsed = started_at.elapsed().as_secs_f64();
+                this.metrics.active_requests.add(-1, this.sm_labels);
+                this.metrics.handled_total.add(1, &smc_labels);
+                this.metrics.handling_duration.record(elapsed, &smc_labels);
+
+                Poll::Ready(Err(err))
+            }
+            Poll::Pending => Poll::Pending,
         }
     }
 }
@@ -0,0 +1,70 @@
+use std::convert::Infallible;
+use std::future::{Ready, ready};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use opentelemetry_prometheus::PrometheusExporter;
+use prometheus::{Encoder, Registry, TextEncoder};
+use tonic::codegen::http::{Request, Response};
+use tower::Service;
+
+/// Renders the metrics recorded through a [`PrometheusExporter`] in the
+/// Prometheus text exposition format on every request, regardless of path
+/// or method. Mount it on an admin/metrics port behind whatever routing
+/// the host application already uses for `/metrics`.
+#[derive(Clone)]
+pub struct PrometheusScrapeService {
+    registry: Registry,
+}
+
+impl PrometheusScrapeService {
+    pub fn new(registry: Registry) -> Self {
+        PrometheusScrapeService { registry }
+    }
+}
+
+/// Builds the scrape service for `exporter`'s registry, ready to be passed
+/// into `MetricsLayerBuilder::with_provider` and mounted alongside it:
+///
+/// ```ignore
+/// let exporter = opentelemetry_prometheus::exporter().build()?;
+/// let metrics_layer = MetricsLayerBuilder::new()
+///     .with_provider(SdkMeterProvider::builder().with_reader(exporter.clone()).build())
+///     .build();
+/// let scrape_service = prometheus_scrape_service(&exporter);
+/// ```
+pub fn prometheus_scrape_service(exporter: &PrometheusExporter) -> PrometheusScrapeService {
+    PrometheusScrapeService::new(exporter.registry().clone())
+}
+
+impl<B> Service<Request<B>> for PrometheusScrapeService {
+    type Response = Response<Full<Bytes>>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request<B>) -> Self::Future {
+        let metric_families = self.registry.gather();
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        let response = match encoder.encode(&metric_families, &mut buffer) {
+            Ok(()) => Response::builder()
+                .status(200)
+                .header("content-type", encoder.format_type())
+                .body(Full::new(Bytes::from(buffer)))
+                .expect("a fixed status and header set always builds a valid response"),
+            Err(err) => Response::builder()
+                .status(500)
+                .body(Full::new(Bytes::from(format!(
+                    "failed to encode metrics: {err}"
+                ))))
+                .expect("a fixed status and header set always builds a valid response"),
+        };
+
+        ready(Ok(response))
+    }
+}
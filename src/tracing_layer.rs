@@ -0,0 +1,294 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http_body::{Body, Frame, SizeHint};
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{Span, SpanKind, Status, TraceContextExt, Tracer, TracerProvider};
+use opentelemetry::{Context as OtelContext, KeyValue};
+use pin_project::pin_project;
+use tonic::Code;
+use tonic::codegen::http::{request, response};
+use tower::{Layer, Service};
+
+/// Reads W3C `traceparent`/`tracestate` headers out of an incoming
+/// request's metadata so the global propagator can extract a parent
+/// context from them.
+struct MetadataExtractor<'a>(&'a tonic::codegen::http::HeaderMap);
+
+impl Extractor for MetadataExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+pub struct TracingLayer<T = BoxedTracer> {
+    tracer: Arc<T>,
+}
+
+impl<T> Clone for TracingLayer<T> {
+    fn clone(&self) -> Self {
+        TracingLayer {
+            tracer: self.tracer.clone(),
+        }
+    }
+}
+
+/// Defaults to a tracer fetched from the global `TracerProvider`; pass a
+/// provider explicitly via `with_provider` to use a scoped one instead,
+/// mirroring `MetricsLayerBuilder::with_provider`.
+pub struct TracingLayerBuilder<T = BoxedTracer> {
+    tracer: T,
+}
+
+impl TracingLayerBuilder<BoxedTracer> {
+    pub fn new() -> Self {
+        TracingLayerBuilder {
+            tracer: global::tracer("tonic"),
+        }
+    }
+}
+
+impl Default for TracingLayerBuilder<BoxedTracer> {
+    fn default() -> Self {
+        TracingLayerBuilder::new()
+    }
+}
+
+impl<T> TracingLayerBuilder<T> {
+    pub fn with_provider<P>(self, provider: P) -> TracingLayerBuilder<P::Tracer>
+    where
+        P: TracerProvider,
+        P::Tracer: Send + Sync + 'static,
+    {
+        TracingLayerBuilder {
+            tracer: provider.tracer("tonic"),
+        }
+    }
+
+    pub fn build(self) -> TracingLayer<T>
+    where
+        T: Tracer + Send + Sync + 'static,
+    {
+        TracingLayer {
+            tracer: Arc::new(self.tracer),
+        }
+    }
+}
+
+impl<S, T> Layer<S> for TracingLayer<T>
+where
+    T: Tracer + Send + Sync + 'static,
+{
+    type Service = TracingService<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TracingService {
+            service: inner,
+            tracer: self.tracer.clone(),
+        }
+    }
+}
+
+pub struct TracingService<S, T = BoxedTracer> {
+    tracer: Arc<T>,
+    service: S,
+}
+
+impl<S: Clone, T> Clone for TracingService<S, T> {
+    fn clone(&self) -> Self {
+        TracingService {
+            tracer: self.tracer.clone(),
+            service: self.service.clone(),
+        }
+    }
+}
+
+impl<S, B, C, T> Service<request::Request<B>> for TracingService<S, T>
+where
+    S: Service<request::Request<B>, Response = response::Response<C>>,
+    T: Tracer + Send + Sync + 'static,
+    T::Span: Send + Sync + 'static,
+{
+    type Response = response::Response<TracingBody<C>>;
+    type Error = S::Error;
+    type Future = TracingFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: request::Request<B>) -> Self::Future {
+        let path = req.uri().path();
+        let (service, method) = path.rsplit_once("/").expect("Path must contain a method");
+        let service = service.to_owned();
+        let method = method.to_owned();
+
+        let parent_cx = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&MetadataExtractor(req.headers()))
+        });
+
+        let span = self
+            .tracer
+            .span_builder(format!("{service}/{method}"))
+            .with_kind(SpanKind::Server)
+            .with_attributes(vec![
+                KeyValue::new("rpc.system", "grpc"),
+                KeyValue::new("rpc.service", service.clone()),
+                KeyValue::new("rpc.method", method.clone()),
+            ])
+            .start_with_context(&*self.tracer, &parent_cx);
+        let cx = parent_cx.with_span(span);
+
+        let _guard = cx.clone().attach();
+        let inner = self.service.call(req);
+
+        TracingFuture { inner, cx }
+    }
+}
+
+#[pin_project]
+pub struct TracingFuture<F> {
+    #[pin]
+    inner: F,
+    cx: OtelContext,
+}
+
+impl<F, B, E> Future for TracingFuture<F>
+where
+    F: Future<Output = Result<response::Response<B>, E>>,
+{
+    type Output = Result<response::Response<TracingBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, task_cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _guard = this.cx.clone().attach();
+
+        match this.inner.poll(task_cx) {
+            Poll::Ready(Ok(response)) => {
+                let header_code = response
+                    .headers()
+                    .get("grpc-status")
+                    .map(|s| Code::from_bytes(s.as_bytes()));
+                let cx = this.cx.clone();
+                let response = response.map(|body| TracingBody::new(body, cx, header_code));
+                Poll::Ready(Ok(response))
+            }
+            Poll::Ready(Err(err)) => {
+                set_status(this.cx, Code::Unknown);
+                Poll::Ready(Err(err))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Sets the span's terminal status/error event from the resolved
+/// `grpc-status` code.
+fn set_status(cx: &OtelContext, code: Code) {
+    let span = cx.span();
+    if code == Code::Ok {
+        span.set_status(Status::Ok);
+    } else {
+        span.set_status(Status::error(format!("{:?}", code)));
+        span.add_event(
+            "exception",
+            vec![KeyValue::new("grpc.status_code", format!("{:?}", code))],
+        );
+    }
+}
+
+/// Bookkeeping carried alongside the wrapped response body: it is the
+/// terminal trailers frame, not the response future resolving, that marks
+/// the span's outcome - this is what makes streaming calls get a status
+/// reflecting the whole call instead of just the initial headers.
+struct SpanState {
+    cx: OtelContext,
+    header_code: Option<Code>,
+    finished: bool,
+}
+
+fn finish(state: &mut SpanState, code: Code) {
+    if state.finished {
+        return;
+    }
+    state.finished = true;
+    set_status(&state.cx, code);
+}
+
+/// Wraps a response body so the span's status is set from the terminal
+/// trailers frame (falling back to headers, then `Ok`) rather than from the
+/// response future resolving, which only ever sees initial headers and
+/// mislabels practically every real RPC `Ok`.
+#[pin_project]
+pub struct TracingBody<B> {
+    #[pin]
+    inner: B,
+    state: SpanState,
+}
+
+impl<B> TracingBody<B> {
+    pub(crate) fn new(inner: B, cx: OtelContext, header_code: Option<Code>) -> Self {
+        TracingBody {
+            inner,
+            state: SpanState {
+                cx,
+                header_code,
+                finished: false,
+            },
+        }
+    }
+}
+
+fn trailer_code(frame: &Frame<impl bytes::Buf>) -> Option<Code> {
+    frame
+        .trailers_ref()
+        .and_then(|trailers| trailers.get("grpc-status"))
+        .map(|status| Code::from_bytes(status.as_bytes()))
+}
+
+impl<B> Body for TracingBody<B>
+where
+    B: Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_frame(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) if frame.is_trailers() => {
+                let code = trailer_code(frame)
+                    .or(this.state.header_code)
+                    .unwrap_or(Code::Ok);
+                finish(this.state, code);
+            }
+            Poll::Ready(None) => {
+                let code = this.state.header_code.unwrap_or(Code::Ok);
+                finish(this.state, code);
+            }
+            _ => {}
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.state.finished && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}